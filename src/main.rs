@@ -3,8 +3,12 @@ extern crate osoy;
 
 use osoy::{gitutil, operator, repo, termion, Config, Exec, Location};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{env, process};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, process, thread};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use terminal_size::{terminal_size, Width};
@@ -24,6 +28,13 @@ enum Opt {
         opt: operator::clone::Opt,
         #[structopt(short, long, help = "Run pacman interactively")]
         interactive: bool,
+        #[structopt(
+            short = "j",
+            long,
+            default_value = "4",
+            help = "Number of concurrent clone jobs"
+        )]
+        jobs: usize,
     },
     #[structopt(about = "List installed packages")]
     List(operator::list::Opt),
@@ -38,11 +49,63 @@ enum Opt {
     Search {
         #[structopt(required = true, min_values = 1, help = Location::about())]
         keywords: Vec<String>,
+        #[structopt(short, long, help = "Select results to install")]
+        install: bool,
+        #[structopt(long, help = "Run pacman interactively")]
+        interactive: bool,
+        #[structopt(short, long, help = "Print executed commands")]
+        verbose: bool,
+        #[structopt(long, help = "Bypass the cache and fetch from the network")]
+        refresh: bool,
+        #[structopt(long, default_value = "86400", help = "Cache entry lifetime in seconds")]
+        ttl: u64,
+        #[structopt(
+            long,
+            default_value = "popularity",
+            possible_values = &["votes", "popularity", "modified"],
+            help = "Sort order"
+        )]
+        sort: SortBy,
+        #[structopt(long = "out-of-date", help = "Only show flagged out-of-date packages")]
+        out_of_date: bool,
+        #[structopt(long = "hide-out-of-date", help = "Hide flagged out-of-date packages")]
+        hide_out_of_date: bool,
+    },
+    #[structopt(alias = "u", about = "Upgrade out-of-date packages")]
+    Upgrade {
+        #[structopt(long = "dry-run", help = "Only list upgradable packages")]
+        dry_run: bool,
+        #[structopt(short, long, help = "Run pacman interactively")]
+        interactive: bool,
+        #[structopt(short, long, help = "Print executed commands")]
+        verbose: bool,
     },
 }
 
+#[derive(Debug)]
+enum SortBy {
+    Popularity,
+    Votes,
+    Modified,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "popularity" => Ok(SortBy::Popularity),
+            "votes" => Ok(SortBy::Votes),
+            "modified" => Ok(SortBy::Modified),
+            _ => Err(format!("invalid sort key '{}'", s)),
+        }
+    }
+}
+
 const AUR_URL: &str = "https://aur.archlinux.org/";
 const TAB_SIZE: usize = 4;
+const DEFAULT_JOBS: usize = 4;
+const RPC_UNAVAILABLE: &str = "request failed";
 
 fn rename_targets(targets: &[Location], fill_empty: bool) -> Vec<Location> {
     match !fill_empty || targets.len() > 0 {
@@ -54,6 +117,22 @@ fn rename_targets(targets: &[Location], fill_empty: bool) -> Vec<Location> {
     }
 }
 
+/// Format a Unix epoch (seconds) as a `YYYY-MM-DD` date in UTC.
+fn format_date(epoch: u64) -> String {
+    let days = (epoch / 86400) as i64;
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let year = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { year + 1 } else { year };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
 fn force_remove_dir(path: &str) -> i32 {
     process::Command::new("rm")
         .args(&["-rf", path])
@@ -65,6 +144,337 @@ fn force_remove_dir(path: &str) -> i32 {
         .unwrap_or(1)
 }
 
+fn makepkg(path: &Path, interactive: bool, verbose: bool) -> i32 {
+    match env::set_current_dir(path) {
+        Ok(_) => {
+            let name = path.file_name().unwrap().to_string_lossy();
+            let cmd = "makepkg";
+            let mut args = vec!["-sirc", &name];
+            if !interactive {
+                args.push("--noconfirm");
+            }
+
+            if verbose {
+                println!("> {} {}", cmd, args.join(" "));
+            }
+
+            process::Command::new(cmd)
+                .args(&args)
+                .stdin(process::Stdio::inherit())
+                .stderr(process::Stdio::inherit())
+                .stdout(process::Stdio::inherit())
+                .env("PWD", path.display().to_string())
+                .status()
+                .ok()
+                .map(|status| status.code())
+                .flatten()
+                .unwrap_or(1)
+        }
+        Err(err) => {
+            info!("could not access '{}': {}", path.display(), err);
+            1
+        }
+    }
+}
+
+fn git_pull(path: &Path, verbose: bool) -> bool {
+    let mut cmd = process::Command::new("git");
+    cmd.args(&["-C", &path.to_string_lossy(), "pull"]);
+    if verbose {
+        println!("> git -C {} pull", path.display());
+    }
+    cmd.status().map(|status| status.success()).unwrap_or(false)
+}
+
+fn fetch_aur_info(names: &[String]) -> Option<Vec<AurPackage>> {
+    let query = names
+        .iter()
+        .map(|name| format!("&arg[]={}", name))
+        .collect::<String>();
+    let res = reqwest::blocking::get(&format!("{}rpc/?v=5&type=info{}", AUR_URL, query)).ok()?;
+    res.json::<AurResponse>().ok().map(|res| res.results)
+}
+
+fn installed_version(name: &str) -> Option<String> {
+    let out = process::Command::new("pacman")
+        .args(&["-Q", name])
+        .output()
+        .ok()?;
+    match out.status.success() {
+        true => String::from_utf8(out.stdout)
+            .ok()?
+            .split_whitespace()
+            .nth(1)
+            .map(|v| v.to_string()),
+        false => None,
+    }
+}
+
+fn vercmp(a: &str, b: &str) -> i32 {
+    process::Command::new("vercmp")
+        .args(&[a, b])
+        .output()
+        .ok()
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .and_then(|out| out.trim().parse::<i32>().ok())
+        .unwrap_or(0)
+}
+
+fn strip_constraint(dep: &str) -> String {
+    dep.split(|c| c == '>' || c == '<' || c == '=')
+        .next()
+        .unwrap_or(dep)
+        .to_string()
+}
+
+fn is_official(name: &str) -> bool {
+    process::Command::new("pacman")
+        .args(&["-Si", name])
+        .output()
+        .map(|out| out.status.success())
+        .unwrap_or(false)
+}
+
+/// Clone the requested AUR targets (together with their recursively resolved
+/// AUR dependencies) and build them with `makepkg` in dependency order.
+fn install(
+    targets: Vec<Location>,
+    interactive: bool,
+    verbose: bool,
+    jobs: usize,
+    config: &Config,
+) -> i32 {
+    let roots: Vec<String> = targets.iter().map(|target| target.id()).collect();
+    let order = match resolve_build_order(&roots) {
+        Ok(order) => order,
+        // If the AUR info RPC is unreachable, fall back to cloning and building
+        // the requested roots directly and let makepkg resolve their deps — the
+        // old behaviour. Genuine errors (cycles, unknown packages) still abort.
+        Err(ref err) if err == RPC_UNAVAILABLE => {
+            info!("{}; building requested packages only", err);
+            roots.clone()
+        }
+        Err(err) => {
+            info!("{}", err);
+            return 1;
+        }
+    };
+    let requested: HashSet<&String> = roots.iter().collect();
+    let targets: Vec<Location> = order
+        .iter()
+        .map(|name| Location::from_str(&format!("{}{}", AUR_URL, name)).unwrap())
+        .collect();
+
+    // Clone every target concurrently with a bounded worker pool; the build
+    // order is preserved by carrying each target's index through the queue.
+    let queue: Arc<Mutex<VecDeque<(usize, Location)>>> =
+        Arc::new(Mutex::new(targets.into_iter().enumerate().collect()));
+    let done: Arc<Mutex<Vec<(usize, PathBuf)>>> = Arc::new(Mutex::new(vec![]));
+    let errors = Arc::new(Mutex::new(0));
+    let src = config.src.clone();
+
+    let mut workers = vec![];
+    for _ in 0..jobs.max(1) {
+        let queue = Arc::clone(&queue);
+        let done = Arc::clone(&done);
+        let errors = Arc::clone(&errors);
+        let src = src.clone();
+        // Each worker owns its own auth cache so clones run truly in parallel
+        // instead of serializing behind a single shared lock.
+        workers.push(thread::spawn(move || {
+            let auth_cache = gitutil::AuthCache::new();
+            loop {
+                let (index, location) = match queue.lock().unwrap().pop_front() {
+                    Some(item) => item,
+                    None => break,
+                };
+                let id = location.id();
+                let path = src.join(&id);
+
+                if path.exists() {
+                    done.lock().unwrap().push((index, path));
+                    continue;
+                }
+
+                match gitutil::clone(&path, &id, &location.url(), &auth_cache) {
+                    Ok(_) => {
+                        done.lock().unwrap().push((index, path));
+                        gitutil::log("done", id);
+                    }
+                    Err(err) => {
+                        *errors.lock().unwrap() += 1;
+                        gitutil::log("failed", id);
+                        if verbose {
+                            gitutil::log("", err);
+                        }
+                        force_remove_dir(&path.to_string_lossy());
+                    }
+                }
+            }
+        }));
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut done = Arc::try_unwrap(done).unwrap().into_inner().unwrap();
+    done.sort_by_key(|(index, _)| *index);
+    let paths: Vec<PathBuf> = done.into_iter().map(|(_, path)| path).collect();
+    let mut errors = Arc::try_unwrap(errors).unwrap().into_inner().unwrap();
+
+    info!("installing...");
+
+    // Only record a package as installed once it actually built, so the
+    // explicit-vs-dependency tracking never includes failed builds.
+    let cache = cache::Cache::open(&cache_path(config)).ok();
+
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let exit_code = makepkg(&path, interactive, verbose);
+        if exit_code != 0 {
+            errors += 1;
+            info!("failed to install '{}' [{}]", name, exit_code)
+        } else if let Some(cache) = &cache {
+            let _ = cache.mark_installed(&name, requested.contains(&name), cache::now());
+        }
+    }
+
+    errors
+}
+
+/// Parse a `1-3,5`-style selection into a de-duplicated, sorted list of
+/// 1-based indices, discarding anything outside `1..=max`.
+fn parse_selection(input: &str, max: usize) -> Vec<usize> {
+    let mut indices = vec![];
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                if let (Ok(lo), Ok(hi)) = (lo.trim().parse::<usize>(), hi.trim().parse::<usize>()) {
+                    indices.extend((lo..=hi).filter(|&i| i >= 1 && i <= max));
+                }
+            }
+            None => {
+                if let Ok(i) = part.parse::<usize>() {
+                    if i >= 1 && i <= max {
+                        indices.push(i);
+                    }
+                }
+            }
+        }
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+/// Expand the AUR dependency graph rooted at `roots` and return the package
+/// names in the order they must be built — leaf dependencies first. Official
+/// repo dependencies are left for `makepkg` to resolve; nodes already
+/// satisfied by `pacman -Q` are skipped unless explicitly requested.
+fn resolve_build_order(roots: &[String]) -> Result<Vec<String>, String> {
+    let mut deps: HashMap<String, Vec<String>> = HashMap::new();
+    let mut aur: HashSet<String> = HashSet::new();
+    let mut queued: HashSet<String> = HashSet::new();
+    let mut frontier: VecDeque<String> = VecDeque::new();
+
+    for name in roots {
+        if queued.insert(name.clone()) {
+            frontier.push_back(name.clone());
+        }
+    }
+
+    while !frontier.is_empty() {
+        let batch: Vec<String> = frontier.drain(..).collect();
+        let infos = fetch_aur_info(&batch).ok_or(RPC_UNAVAILABLE)?;
+
+        for name in &batch {
+            let pkg = match infos.iter().find(|pkg| &pkg.name == name) {
+                Some(pkg) => pkg,
+                None => continue,
+            };
+            aur.insert(name.clone());
+
+            let mut edges = vec![];
+            for raw in pkg
+                .depends
+                .iter()
+                .chain(&pkg.make_depends)
+                .chain(&pkg.check_depends)
+            {
+                let dep = strip_constraint(raw);
+                if dep.is_empty() || is_official(&dep) {
+                    continue;
+                }
+                edges.push(dep.clone());
+                if queued.insert(dep.clone()) {
+                    frontier.push_back(dep);
+                }
+            }
+            deps.insert(name.clone(), edges);
+        }
+    }
+
+    for root in roots {
+        if !aur.contains(root) {
+            return Err(format!("package '{}' not found in the AUR", root));
+        }
+    }
+
+    // Keep only edges that point at packages actually hosted on the AUR;
+    // anything else is a provided/virtual package makepkg will handle.
+    let mut incoming: HashMap<String, usize> = aur.iter().map(|n| (n.clone(), 0)).collect();
+    let mut outgoing: HashMap<String, Vec<String>> = HashMap::new();
+    for node in &aur {
+        let edges: Vec<String> = deps
+            .get(node)
+            .map(|e| e.iter().filter(|d| aur.contains(*d)).cloned().collect())
+            .unwrap_or_default();
+        for dep in &edges {
+            *incoming.get_mut(dep).unwrap() += 1;
+        }
+        outgoing.insert(node.clone(), edges);
+    }
+
+    // Kahn's algorithm starting from the nodes nothing depends on (the
+    // requested roots); we want dependencies before dependents, so order by
+    // resolving nodes with no remaining dependents first and reversing at the end.
+    let mut ready: VecDeque<String> = incoming
+        .iter()
+        .filter(|(_, &count)| count == 0)
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut order = vec![];
+    while let Some(node) = ready.pop_front() {
+        order.push(node.clone());
+        if let Some(edges) = outgoing.get(&node) {
+            for dep in edges {
+                let count = incoming.get_mut(dep).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push_back(dep.clone());
+                }
+            }
+        }
+    }
+
+    if order.len() != aur.len() {
+        return Err("dependency cycle detected".into());
+    }
+
+    // `order` lists dependents before dependencies; reverse for build order.
+    order.reverse();
+
+    let requested: HashSet<&String> = roots.iter().collect();
+    Ok(order
+        .into_iter()
+        .filter(|name| requested.contains(name) || installed_version(name).is_none())
+        .collect())
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct AurPackage {
@@ -79,6 +489,12 @@ struct AurPackage {
     maintainer: Option<String>,
     first_submitted: u64,
     last_modified: u64,
+    #[serde(default)]
+    depends: Vec<String>,
+    #[serde(default)]
+    make_depends: Vec<String>,
+    #[serde(default)]
+    check_depends: Vec<String>,
 }
 
 impl AurPackage {
@@ -112,7 +528,18 @@ impl AurPackage {
                 .unwrap_or("".into()),
             self.name,
             self.version.map(|v| format!(" {}", v)).unwrap_or("".into()),
-            format!(" [{}]", self.popularity),
+            format!(" [{} | {} votes]", self.popularity, self.num_votes),
+            match self.first_submitted {
+                0 => "".into(),
+                submitted => format!(
+                    " [submitted {} | updated {}]",
+                    format_date(submitted),
+                    format_date(self.last_modified)
+                ),
+            },
+            self.out_of_date
+                .map(|epoch| format!(" [out of date since {}]", format_date(epoch)))
+                .unwrap_or("".into()),
             description,
         ]
         .join("")
@@ -124,107 +551,375 @@ struct AurResponse {
     results: Vec<AurPackage>,
 }
 
+/// Path of the SQLite metadata cache, kept as a sibling of the `aur` clone
+/// directory (e.g. `<src>/aur` -> `<src>/aur.db`).
+fn cache_path(config: &Config) -> PathBuf {
+    let mut path = config.src.clone();
+    path.set_extension("db");
+    path
+}
+
+/// Persistent cache of AUR package metadata and recent search results, used to
+/// avoid re-hitting the network and re-parsing JSON on repeated lookups.
+mod cache {
+    use super::AurPackage;
+    use rusqlite::{params, Connection};
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    pub struct Cache {
+        conn: Connection,
+    }
+
+    impl Cache {
+        pub fn open(path: &Path) -> rusqlite::Result<Self> {
+            let conn = Connection::open(path)?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS packages (
+                    name TEXT PRIMARY KEY,
+                    version TEXT,
+                    description TEXT,
+                    popularity REAL,
+                    num_votes INTEGER,
+                    out_of_date INTEGER,
+                    first_submitted INTEGER,
+                    last_modified INTEGER,
+                    maintainer TEXT,
+                    fetched INTEGER NOT NULL,
+                    explicit INTEGER NOT NULL DEFAULT 0
+                );
+                CREATE TABLE IF NOT EXISTS searches (
+                    query TEXT PRIMARY KEY,
+                    names TEXT NOT NULL,
+                    fetched INTEGER NOT NULL
+                );",
+            )?;
+            Ok(Self { conn })
+        }
+
+        /// Upsert a package's metadata, leaving the `explicit` flag untouched.
+        pub fn store(&self, pkg: &AurPackage, fetched: u64) -> rusqlite::Result<()> {
+            self.conn.execute(
+                "INSERT INTO packages
+                    (name, version, description, popularity, num_votes,
+                     out_of_date, first_submitted, last_modified, maintainer, fetched)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(name) DO UPDATE SET
+                    version = excluded.version,
+                    description = excluded.description,
+                    popularity = excluded.popularity,
+                    num_votes = excluded.num_votes,
+                    out_of_date = excluded.out_of_date,
+                    first_submitted = excluded.first_submitted,
+                    last_modified = excluded.last_modified,
+                    maintainer = excluded.maintainer,
+                    fetched = excluded.fetched",
+                params![
+                    pkg.name,
+                    pkg.version,
+                    pkg.description,
+                    pkg.popularity,
+                    pkg.num_votes,
+                    pkg.out_of_date,
+                    pkg.first_submitted,
+                    pkg.last_modified,
+                    pkg.maintainer,
+                    fetched,
+                ],
+            )?;
+            Ok(())
+        }
+
+        pub fn remember_search(
+            &self,
+            query: &str,
+            names: &[String],
+            fetched: u64,
+        ) -> rusqlite::Result<()> {
+            self.conn.execute(
+                "INSERT INTO searches (query, names, fetched) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(query) DO UPDATE SET
+                    names = excluded.names,
+                    fetched = excluded.fetched",
+                params![query, names.join("\n"), fetched],
+            )?;
+            Ok(())
+        }
+
+        /// Return the cached results for `query` if they exist and are no older
+        /// than `ttl` seconds.
+        pub fn search(&self, query: &str, ttl: u64) -> Option<Vec<AurPackage>> {
+            let (names, fetched): (String, u64) = self
+                .conn
+                .query_row(
+                    "SELECT names, fetched FROM searches WHERE query = ?1",
+                    params![query],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .ok()?;
+            if now().saturating_sub(fetched) > ttl {
+                return None;
+            }
+            Some(
+                names
+                    .split('\n')
+                    .filter(|name| !name.is_empty())
+                    .filter_map(|name| self.get(name))
+                    .collect(),
+            )
+        }
+
+        pub fn get(&self, name: &str) -> Option<AurPackage> {
+            self.conn
+                .query_row(
+                    "SELECT version, description, popularity, num_votes,
+                            out_of_date, first_submitted, last_modified, maintainer
+                     FROM packages WHERE name = ?1",
+                    params![name],
+                    |row| {
+                        Ok(AurPackage {
+                            name: name.to_string(),
+                            version: row.get(0)?,
+                            description: row.get(1)?,
+                            url: None,
+                            popularity: row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+                            num_votes: row.get::<_, Option<u64>>(3)?.unwrap_or(0),
+                            out_of_date: row.get(4)?,
+                            first_submitted: row.get::<_, Option<u64>>(5)?.unwrap_or(0),
+                            last_modified: row.get::<_, Option<u64>>(6)?.unwrap_or(0),
+                            maintainer: row.get(7)?,
+                            depends: vec![],
+                            make_depends: vec![],
+                            check_depends: vec![],
+                        })
+                    },
+                )
+                .ok()
+        }
+
+        /// Record that `name` is installed, promoting it to an explicit install
+        /// if requested (a dependency pulled in later never demotes a package
+        /// the user asked for by name).
+        pub fn mark_installed(
+            &self,
+            name: &str,
+            explicit: bool,
+            fetched: u64,
+        ) -> rusqlite::Result<()> {
+            self.conn.execute(
+                "INSERT INTO packages (name, fetched, explicit) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(name) DO UPDATE SET explicit = max(explicit, excluded.explicit)",
+                params![name, fetched, explicit as i64],
+            )?;
+            Ok(())
+        }
+    }
+}
+
 impl Exec for Opt {
     fn exec(self, config: Config) -> i32 {
         match self {
-            Opt::Search { keywords } => {
-                let res = match reqwest::blocking::get(&format!(
-                    "{}rpc/?v=5&type=search&arg={}",
-                    AUR_URL,
-                    keywords.join(" ")
-                )) {
-                    Ok(res) => res,
-                    Err(_) => {
-                        info!("request failed");
-                        return 1;
-                    }
+            Opt::Search {
+                keywords,
+                install: do_install,
+                interactive,
+                verbose,
+                refresh,
+                ttl,
+                sort,
+                out_of_date,
+                hide_out_of_date,
+            } => {
+                let query = keywords.join(" ");
+                let cache = cache::Cache::open(&cache_path(&config)).ok();
+
+                let cached = match refresh {
+                    true => None,
+                    false => cache.as_ref().and_then(|cache| cache.search(&query, ttl)),
                 };
-                let AurResponse { mut results } = match res.json::<AurResponse>() {
-                    Ok(res) => res,
-                    Err(_) => {
-                        info!("could not parse response");
-                        return 1;
+
+                let mut results = match cached {
+                    Some(results) => results,
+                    None => {
+                        let res = match reqwest::blocking::get(&format!(
+                            "{}rpc/?v=5&type=search&arg={}",
+                            AUR_URL, query
+                        )) {
+                            Ok(res) => res,
+                            Err(_) => {
+                                info!("request failed");
+                                return 1;
+                            }
+                        };
+                        let AurResponse { results } = match res.json::<AurResponse>() {
+                            Ok(res) => res,
+                            Err(_) => {
+                                info!("could not parse response");
+                                return 1;
+                            }
+                        };
+                        if let Some(cache) = &cache {
+                            let fetched = cache::now();
+                            for pkg in &results {
+                                let _ = cache.store(pkg, fetched);
+                            }
+                            let names: Vec<String> =
+                                results.iter().map(|pkg| pkg.name.clone()).collect();
+                            let _ = cache.remember_search(&query, &names, fetched);
+                        }
+                        results
                     }
                 };
-                results.sort_unstable_by_key(|pkg| (pkg.popularity * -1000.0) as i64);
+                if out_of_date {
+                    results.retain(|pkg| pkg.out_of_date.is_some());
+                } else if hide_out_of_date {
+                    results.retain(|pkg| pkg.out_of_date.is_none());
+                }
+
+                match sort {
+                    SortBy::Popularity => {
+                        results.sort_unstable_by_key(|pkg| (pkg.popularity * -1000.0) as i64)
+                    }
+                    SortBy::Votes => {
+                        results.sort_unstable_by_key(|pkg| std::cmp::Reverse(pkg.num_votes))
+                    }
+                    SortBy::Modified => {
+                        results.sort_unstable_by_key(|pkg| std::cmp::Reverse(pkg.last_modified))
+                    }
+                }
                 let cols = terminal_size().map(|(Width(w), _)| w as usize);
-                for pkg in results {
-                    println!("{}", pkg.into_search_entry(cols));
+
+                if !do_install {
+                    for pkg in results {
+                        println!("{}", pkg.into_search_entry(cols));
+                    }
+                    return 0;
+                }
+
+                if results.is_empty() {
+                    info!("no results");
+                    return 0;
+                }
+
+                let names: Vec<String> = results.iter().map(|pkg| pkg.name.clone()).collect();
+                for (i, pkg) in results.into_iter().enumerate() {
+                    println!("[{}] {}", i + 1, pkg.into_search_entry(cols));
                 }
-                0
+
+                print!("select packages (e.g. 1-3,5): ");
+                let _ = stdout().flush();
+                let mut input = String::new();
+                if stdin().read_line(&mut input).is_err() {
+                    info!("could not read selection");
+                    return 1;
+                }
+
+                let selected: Vec<String> = parse_selection(&input, names.len())
+                    .into_iter()
+                    .map(|i| names[i - 1].clone())
+                    .collect();
+                if selected.is_empty() {
+                    info!("nothing selected");
+                    return 0;
+                }
+
+                let targets = rename_targets(
+                    &selected
+                        .iter()
+                        .map(|name| Location::from_str(name).unwrap())
+                        .collect::<Vec<_>>(),
+                    false,
+                );
+                install(targets, interactive, verbose, DEFAULT_JOBS, &config)
             }
 
             Opt::Install {
                 mut opt,
                 interactive,
+                jobs,
             } => {
                 opt.targets = rename_targets(&opt.targets, false);
-                let auth_cache = gitutil::AuthCache::new();
-                let mut errors = 0;
-                let mut paths = vec![];
-
-                for location in opt.targets {
-                    let id = location.id();
-                    let path = config.src.join(&id);
-
-                    if path.exists() {
-                        paths.push(path);
-                    } else {
-                        match gitutil::clone(&path, &id, &location.url(), &auth_cache) {
-                            Ok(_) => {
-                                paths.push(path);
-                                gitutil::log("done", id);
-                            }
-                            Err(err) => {
-                                errors += 1;
-                                gitutil::log("failed", id);
-                                if opt.verbose {
-                                    gitutil::log("", err);
-                                }
-                                force_remove_dir(&path.to_string_lossy());
-                            }
+                install(opt.targets, interactive, opt.verbose, jobs, &config)
+            }
+
+            Opt::Upgrade {
+                dry_run,
+                interactive,
+                verbose,
+            } => {
+                let entries = match fs::read_dir(&config.src) {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        info!("could not read '{}': {}", config.src.display(), err);
+                        return 1;
+                    }
+                };
+                let names: Vec<String> = entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect();
+
+                if names.is_empty() {
+                    info!("no packages installed");
+                    return 0;
+                }
+
+                let remote = match fetch_aur_info(&names) {
+                    Some(remote) => remote,
+                    None => {
+                        info!("request failed");
+                        return 1;
+                    }
+                };
+
+                let mut upgradable = vec![];
+                for pkg in remote {
+                    let new = match pkg.version {
+                        Some(new) => new,
+                        None => continue,
+                    };
+                    if let Some(old) = installed_version(&pkg.name) {
+                        if vercmp(&new, &old) > 0 {
+                            upgradable.push((pkg.name, old, new));
                         }
                     }
                 }
 
-                info!("installing...");
+                if upgradable.is_empty() {
+                    info!("all packages are up to date");
+                    return 0;
+                }
 
-                for path in paths {
-                    match env::set_current_dir(&path) {
-                        Ok(_) => {
-                            let name = path.file_name().unwrap().to_string_lossy();
-                            let cmd = "makepkg";
-                            let mut args = vec!["-sirc", &name];
-                            if !interactive {
-                                args.push("--noconfirm");
-                            }
+                if dry_run {
+                    for (name, old, new) in &upgradable {
+                        println!("{} {} -> {}", name, old, new);
+                    }
+                    return 0;
+                }
 
-                            if opt.verbose {
-                                println!("> {} {}", cmd, args.join(" "));
-                            }
+                let mut errors = 0;
 
-                            let exit_code = process::Command::new(cmd)
-                                .args(&args)
-                                .stdin(process::Stdio::inherit())
-                                .stderr(process::Stdio::inherit())
-                                .stdout(process::Stdio::inherit())
-                                .env("PWD", path.display().to_string())
-                                .status()
-                                .ok()
-                                .map(|status| status.code())
-                                .flatten()
-                                .map(|code| code)
-                                .unwrap_or(1);
-
-                            if exit_code != 0 {
-                                errors += 1;
-                                info!("failed to install '{}' [{}]", name, exit_code)
-                            }
-                        }
-                        Err(err) => {
-                            errors += 1;
-                            info!("could not access '{}': {}", path.display(), err)
-                        }
+                for (name, _, _) in &upgradable {
+                    let path = config.src.join(name);
+                    if !git_pull(&path, verbose) {
+                        errors += 1;
+                        gitutil::log("failed", name);
+                        continue;
+                    }
+                    gitutil::log("done", name);
+
+                    let exit_code = makepkg(&path, interactive, verbose);
+                    if exit_code != 0 {
+                        errors += 1;
+                        info!("failed to upgrade '{}' [{}]", name, exit_code)
                     }
                 }
 